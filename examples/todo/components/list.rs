@@ -1,13 +1,13 @@
 use super::task::TaskComponent;
 use crate::{AppState, Tasks};
-use gflux::{Component, ComponentCtx, ComponentHandle};
+use gflux::{Component, ComponentCtx, KeyedChildren};
 use gtk::prelude::*;
-use std::collections::{BTreeMap, HashSet};
 
 pub struct ListComponent {
     vbox: gtk::Box,
+    tasks_box: gtk::Box,
     summary: gtk::Label,
-    task_comps: BTreeMap<u64, ComponentHandle<TaskComponent>>,
+    task_comps: KeyedChildren<u64, TaskComponent>,
 }
 
 impl Component for ListComponent {
@@ -33,22 +33,25 @@ impl Component for ListComponent {
         let summary = gtk::Label::new(None);
         summary.set_halign(gtk::Align::Start);
 
+        let tasks_box = gtk::Box::new(gtk::Orientation::Vertical, 10);
+
         vbox.append(&entry);
         vbox.append(&summary);
+        vbox.append(&tasks_box);
 
-        let task_comps = BTreeMap::new();
+        let task_comps = KeyedChildren::new(&tasks_box);
 
         // rebuild will be called immediately afterwards
         Self {
             vbox,
+            tasks_box,
             summary,
             task_comps,
         }
     }
 
     fn rebuild(&mut self, ctx: ComponentCtx<Self>) {
-        let task_ids: HashSet<u64> = ctx.with_model(|task| task.map.keys().copied().collect());
-        let comp_task_ids: HashSet<u64> = self.task_comps.keys().copied().collect();
+        let task_ids: Vec<u64> = ctx.with_model(|task| task.map.keys().copied().collect());
 
         let num_all = task_ids.len();
         let num_done = ctx.with_model(|task| task.map.values().filter(|t| t.done).count());
@@ -59,21 +62,16 @@ impl Component for ListComponent {
             num_all, num_todo, num_done
         ));
 
-        // Remove components that are no longer in the model
-        for task_id in comp_task_ids.difference(&task_ids) {
-            self.vbox
-                .remove(&self.task_comps.get(task_id).unwrap().widget());
-            self.task_comps.remove(task_id);
-        }
-
-        // Create components for new tasks
-        for task_id in task_ids.difference(&comp_task_ids).copied() {
-            let c = ctx.create_child(
-                move |tasks| tasks.map.get_mut(&task_id).unwrap(),
-                ctx.clone(),
-            );
-            self.vbox.append(&c.widget());
-            self.task_comps.insert(task_id, c);
-        }
+        // Let the keyed collection create, remove, and reorder task widgets to
+        // match the current set of task ids.
+        let tasks_box = self.tasks_box.clone();
+        self.task_comps.reconcile(
+            &ctx,
+            task_ids,
+            |task_id| move |tasks: &mut Tasks| tasks.map.get_mut(&task_id).unwrap(),
+            |_| ctx.clone(),
+            |widget| tasks_box.append(widget),
+            |widget| tasks_box.remove(widget),
+        );
     }
 }
@@ -4,11 +4,15 @@
 #![warn(rustdoc::all)]
 #![warn(missing_debug_implementations)]
 
+use std::any::{Any, TypeId};
 use std::cell::RefCell;
-use std::collections::{BTreeSet, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt;
+use std::hash::Hash;
 use std::rc::{Rc, Weak};
 
+use gtk::prelude::*;
+
 /// The trait that defines a component
 pub trait Component {
     /// The global application state
@@ -26,6 +30,36 @@ pub trait Component {
     fn build(ctx: ComponentCtx<Self>, params: Self::Params) -> Self;
     /// Runs after building and after model is mutated
     fn rebuild(&mut self, ctx: ComponentCtx<Self>);
+
+    /// Runs once after a batch of rebuilds, when this component's subtree has
+    /// been fully (re)materialized.  Because it fires after every dirty
+    /// component in the batch has been rebuilt, children created by a parent's
+    /// rebuild already exist by the time the parent's hook runs, making it the
+    /// safe place to call things like `grab_focus`, scroll to a new row, or
+    /// start geometry-dependent animations.  The default does nothing.
+    fn after_rebuild(&mut self, _ctx: ComponentCtx<Self>) {}
+
+    /// Snapshot the component's model so that redundant rebuilds can be
+    /// skipped.  The default returns `None`, which opts the component out of
+    /// memoization and makes it rebuild whenever a descendant is dirty.  A
+    /// component whose `Model: PartialEq + Clone` opts in by returning
+    /// `Some(model.clone())` and overriding [`should_rebuild`].
+    ///
+    /// [`should_rebuild`]: Component::should_rebuild
+    fn snapshot(_model: &Self::Model) -> Option<Self::Model> {
+        None
+    }
+
+    /// Decide whether a rebuild triggered by a dirty descendant is
+    /// worthwhile, given the model as it was at the last rebuild (`prev`) and
+    /// as it is now (`next`).  The default always rebuilds.  Only consulted for
+    /// components that also override [`snapshot`]; a component marked dirty
+    /// directly through [`ComponentCtx::with_model_mut`] always rebuilds.
+    ///
+    /// [`snapshot`]: Component::snapshot
+    fn should_rebuild(_prev: &Self::Model, _next: &Self::Model) -> bool {
+        true
+    }
 }
 
 /// Manages the component tree
@@ -67,6 +101,10 @@ impl<M> ComponentTree<M> {
         let mut all_dirty = BTreeSet::new();
         let mut new_dirty = BTreeSet::new();
         let mut dirty_parents = BTreeSet::new();
+        // The components dirtied directly through `with_model_mut` always
+        // rebuild; ancestors pulled in below may be skipped by memoization.
+        let directly_dirty: BTreeSet<ComponentId> =
+            self.comp_table.borrow().dirty.iter().copied().collect();
         for cid in &self.comp_table.borrow().dirty {
             new_dirty.insert(*cid);
         }
@@ -86,6 +124,7 @@ impl<M> ComponentTree<M> {
             all_dirty.append(&mut new_dirty);
             new_dirty.append(&mut dirty_parents);
         }
+        let mut rebuilt = Vec::new();
         for cid in &all_dirty {
             let weak_c = self
                 .comp_table
@@ -94,7 +133,24 @@ impl<M> ComponentTree<M> {
                 .get(cid)
                 .and_then(|c| c.upgrade());
             if let Some(c) = weak_c {
-                c.borrow_mut().rebuild();
+                if c.borrow_mut().maybe_rebuild(directly_dirty.contains(cid)) {
+                    rebuilt.push(*cid);
+                }
+            }
+        }
+
+        // Second pass: only components that were actually rebuilt above had
+        // their subtree (re)materialized, so run the post-rebuild hooks on
+        // those alone — not on ancestors skipped by memoization.
+        for cid in &rebuilt {
+            let weak_c = self
+                .comp_table
+                .borrow_mut()
+                .map
+                .get(cid)
+                .and_then(|c| c.upgrade());
+            if let Some(c) = weak_c {
+                c.borrow_mut().after_rebuild();
             }
         }
 
@@ -117,27 +173,49 @@ impl<M> ComponentTree<M> {
             lens: Rc::new(lens),
         };
 
-        let mut component = C::build(ctx.clone(), params);
-        component.rebuild(ctx.clone());
+        let component = C::build(ctx.clone(), params);
         let c = Rc::new(RefCell::new(ComponentBase {
             ctx: ctx.clone(),
             component,
+            last_model: None,
         }));
 
         ctx.id = ctx
             .comp_table
             .borrow_mut()
-            .insert(id, Rc::downgrade(&c) as WeakComponentBase);
+            .insert(id, None, Rc::downgrade(&c) as WeakComponentBase);
+
+        // Rebuild only after the component is registered, so that a context
+        // provided during the first rebuild is visible to children it creates.
+        c.borrow_mut().rebuild();
 
         ComponentHandle { inner: c }
     }
 }
 
-#[derive(Debug)]
 struct ComponentTable {
     pub next_id: ComponentId,
     pub map: HashMap<ComponentId, WeakComponentBase>,
     pub dirty: HashSet<ComponentId>,
+    /// Each component's parent, recorded at registration so that context
+    /// lookups can walk the tree without borrowing any component cell.
+    pub parent: HashMap<ComponentId, Option<ComponentId>>,
+    /// Values provided through [`ComponentCtx::provide_context`], keyed by the
+    /// providing component and then by the value's concrete type.  Kept here,
+    /// outside the component's `RefCell`, so that a component can provide or
+    /// read context during its own `build`/`rebuild`/`after_rebuild`.
+    pub contexts: HashMap<ComponentId, HashMap<TypeId, Rc<dyn Any>>>,
+}
+
+impl fmt::Debug for ComponentTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ComponentTable")
+            .field("next_id", &self.next_id)
+            .field("map", &self.map)
+            .field("dirty", &self.dirty)
+            .field("parent", &self.parent)
+            .finish_non_exhaustive()
+    }
 }
 
 impl ComponentTable {
@@ -146,6 +224,8 @@ impl ComponentTable {
             next_id: 1,
             map: HashMap::new(),
             dirty: HashSet::new(),
+            parent: HashMap::new(),
+            contexts: HashMap::new(),
         }
     }
 
@@ -155,8 +235,14 @@ impl ComponentTable {
         id
     }
 
-    fn insert(&mut self, cid: ComponentId, c: WeakComponentBase) -> ComponentId {
+    fn insert(
+        &mut self,
+        cid: ComponentId,
+        parent_id: Option<ComponentId>,
+        c: WeakComponentBase,
+    ) -> ComponentId {
         self.map.insert(cid, c);
+        self.parent.insert(cid, parent_id);
         cid
     }
 
@@ -171,6 +257,8 @@ impl ComponentTable {
     fn destroy(&mut self, cid: ComponentId) {
         self.map.remove(&cid);
         self.dirty.remove(&cid);
+        self.parent.remove(&cid);
+        self.contexts.remove(&cid);
     }
 }
 
@@ -197,6 +285,10 @@ impl<C: Component> ComponentHandle<C> {
 struct ComponentBase<C: Component> {
     ctx: ComponentCtx<C>,
     component: C,
+    /// The model as it was at the last rebuild, used to skip redundant
+    /// rebuilds.  `None` until the first rebuild or when the component opts out
+    /// of memoization (see [`Component::snapshot`]).
+    last_model: Option<C::Model>,
 }
 
 impl<C: Component> ComponentBaseTrait for ComponentBase<C> {
@@ -207,7 +299,26 @@ impl<C: Component> ComponentBaseTrait for ComponentBase<C> {
         self.ctx.parent_id
     }
     fn rebuild(&mut self) {
+        self.maybe_rebuild(true);
+    }
+    fn maybe_rebuild(&mut self, force: bool) -> bool {
+        if !force {
+            // Consult the stored snapshot: skip the rebuild when the
+            // component's own slice of the model hasn't meaningfully changed.
+            let skip = self.ctx.with_model(|next| match &self.last_model {
+                Some(prev) => !C::should_rebuild(prev, next),
+                None => false,
+            });
+            if skip {
+                return false;
+            }
+        }
         self.component.rebuild(self.ctx.clone());
+        self.last_model = self.ctx.with_model(C::snapshot);
+        true
+    }
+    fn after_rebuild(&mut self) {
+        self.component.after_rebuild(self.ctx.clone());
     }
 }
 
@@ -221,6 +332,10 @@ trait ComponentBaseTrait {
     fn id(&self) -> ComponentId;
     fn parent_id(&self) -> Option<ComponentId>;
     fn rebuild(&mut self);
+    /// Rebuild unless memoization says the component's slice is unchanged.
+    /// Returns `true` if `rebuild` actually ran.
+    fn maybe_rebuild(&mut self, force: bool) -> bool;
+    fn after_rebuild(&mut self);
 }
 
 type ComponentId = u64;
@@ -287,21 +402,71 @@ impl<C: Component> ComponentCtx<C> {
             global: self.global.clone(),
             lens: child_lens,
         };
-        let mut component = K::build(ctx.clone(), params);
-        component.rebuild(ctx.clone());
+        let component = K::build(ctx.clone(), params);
         let c = Rc::new(RefCell::new(ComponentBase {
             ctx: ctx.clone(),
             component,
+            last_model: None,
         }));
 
         ctx.id = ctx
             .comp_table
             .borrow_mut()
-            .insert(id, Rc::downgrade(&c) as WeakComponentBase);
+            .insert(id, Some(self.id), Rc::downgrade(&c) as WeakComponentBase);
+
+        // Rebuild only after the component is registered, so that a context
+        // provided during the first rebuild is visible to children it creates.
+        c.borrow_mut().rebuild();
 
         ComponentHandle { inner: c }
     }
 
+    /// Provide a value that any descendant of this component can later read
+    /// with [`use_context`], without threading it through every intermediate
+    /// component's `Params`.  Values are keyed by their concrete type, so
+    /// providing a second value of the same type replaces the first.  A
+    /// descendant's lookup stops at the nearest ancestor that provided the
+    /// type, so a component may override a value provided higher up.
+    ///
+    /// [`use_context`]: Self::use_context
+    pub fn provide_context<T: 'static>(&self, value: T) {
+        self.comp_table
+            .borrow_mut()
+            .contexts
+            .entry(self.id)
+            .or_default()
+            .insert(TypeId::of::<T>(), Rc::new(value));
+    }
+
+    /// Look up a value of type `T` provided by this component or one of its
+    /// ancestors with [`provide_context`], walking up the `parent_id` chain and
+    /// returning the nearest match.  Returns `None` if no ancestor provided the
+    /// type.
+    ///
+    /// The lookup reads the context map stored on the component table, so it is
+    /// safe to call during the caller's own `build`/`rebuild`/`after_rebuild`
+    /// without re-borrowing any component.
+    ///
+    /// [`provide_context`]: Self::provide_context
+    pub fn use_context<T: 'static>(&self) -> Option<Rc<T>> {
+        let type_id = TypeId::of::<T>();
+        let table = self.comp_table.borrow();
+        let mut cur = Some(self.id);
+        while let Some(cid) = cur {
+            if let Some(any) = table.contexts.get(&cid).and_then(|m| m.get(&type_id)) {
+                return any.clone().downcast::<T>().ok();
+            }
+            // `self` may not be registered yet (during its own `build`), so
+            // fall back to the parent recorded on the live ctx for that step.
+            cur = match table.parent.get(&cid) {
+                Some(parent) => *parent,
+                None if cid == self.id => self.parent_id,
+                None => None,
+            };
+        }
+        None
+    }
+
     /// Access the component state
     pub fn with_model<R, F: Fn(&C::Model) -> R>(&self, f: F) -> R {
         let mut global = self.global.borrow_mut();
@@ -326,4 +491,173 @@ impl<C: Component> ComponentCtx<C> {
         }
         r
     }
+
+    /// Drive a future on the glib main context and feed its result back into
+    /// this component's model when it resolves.
+    ///
+    /// This is the asynchronous counterpart to [`with_model_mut`]: where that
+    /// method mutates state synchronously from a signal handler, `spawn` is for
+    /// work that finishes later, such as an HTTP request, file IO, or a timer.
+    /// When `fut` completes, `apply` is called with the component's model and
+    /// the future's output, the component is marked dirty, and the first-change
+    /// callbacks fire, exactly like [`with_model_mut`].
+    ///
+    /// If the component has been dropped by the time the future resolves, the
+    /// result is silently discarded.
+    ///
+    /// [`with_model_mut`]: Self::with_model_mut
+    pub fn spawn<Fut, R, F>(&self, fut: Fut, apply: F)
+    where
+        Fut: std::future::Future<Output = R> + 'static,
+        F: Fn(&mut C::Model, R) + 'static,
+        C::Model: 'static,
+        C::GlobalModel: 'static,
+    {
+        let global = self.global.clone();
+        let comp_table = self.comp_table.clone();
+        let change_cbs = self.change_cbs.clone();
+        let lens = self.lens.clone();
+        let id = self.id;
+
+        glib::MainContext::default().spawn_local(async move {
+            let output = fut.await;
+
+            // The component may have been dropped while the future was
+            // pending.  If it's gone from the table, don't resurrect it.
+            let alive = comp_table
+                .borrow()
+                .map
+                .get(&id)
+                .and_then(|c| c.upgrade())
+                .is_some();
+            if !alive {
+                return;
+            }
+
+            let was_clean = comp_table.borrow().is_clean();
+            comp_table.borrow_mut().mark_dirty(id);
+
+            {
+                let mut global = global.borrow_mut();
+                apply(lens(&mut global), output);
+            }
+
+            if was_clean {
+                let change_cbs = change_cbs.borrow();
+                for cb in &*change_cbs {
+                    cb()
+                }
+            }
+        });
+    }
+}
+
+/// A reusable, keyed collection of child components attached to a GTK
+/// container.
+///
+/// List-bearing components otherwise hand-roll the same diff on every rebuild:
+/// work out which keys are new, which have vanished, create and destroy the
+/// corresponding children, and append or remove their widgets.  `KeyedChildren`
+/// owns that diff.  Unlike an append/remove-only diff, [`reconcile`] also
+/// reorders the surviving widgets to match the order of the desired keys, so
+/// sorted or filtered lists stay in the right order.
+///
+/// [`reconcile`]: KeyedChildren::reconcile
+pub struct KeyedChildren<K, C: Component>
+where
+    C::Widget: IsA<gtk::Widget>,
+{
+    parent: gtk::Widget,
+    children: BTreeMap<K, ComponentHandle<C>>,
+}
+
+impl<K: fmt::Debug, C: Component> fmt::Debug for KeyedChildren<K, C>
+where
+    C::Widget: IsA<gtk::Widget>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyedChildren")
+            .field("keys", &self.children.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl<K, C> KeyedChildren<K, C>
+where
+    K: Ord + Hash + Clone,
+    C: Component + 'static,
+    C::Widget: IsA<gtk::Widget>,
+    C::Model: 'static,
+    C::GlobalModel: 'static,
+{
+    /// Create an empty collection whose children are attached under `parent`.
+    pub fn new(parent: &(impl IsA<gtk::Widget> + Clone)) -> Self {
+        Self {
+            parent: parent.clone().upcast(),
+            children: BTreeMap::new(),
+        }
+    }
+
+    /// Diff the current children against `keys` and patch the tree to match.
+    ///
+    /// Handles for keys no longer in `keys` are dropped after `detach` is
+    /// called with their widget; new keys get a child created through
+    /// `ctx.create_child` (using the lens returned by `make_lens` and the
+    /// params returned by `make_params`) whose widget is passed to `attach`.
+    /// Finally the surviving widgets are reordered to match the iteration order
+    /// of `keys`.
+    pub fn reconcile<P, I, L, LF, MP, A, D>(
+        &mut self,
+        ctx: &ComponentCtx<P>,
+        keys: I,
+        make_lens: L,
+        make_params: MP,
+        attach: A,
+        detach: D,
+    ) where
+        P: Component<GlobalModel = C::GlobalModel>,
+        P::Model: 'static,
+        I: IntoIterator<Item = K>,
+        L: Fn(K) -> LF,
+        LF: Fn(&mut P::Model) -> &mut C::Model + 'static,
+        MP: Fn(&K) -> C::Params,
+        A: Fn(&C::Widget),
+        D: Fn(&C::Widget),
+    {
+        let desired: Vec<K> = keys.into_iter().collect();
+        let desired_set: BTreeSet<K> = desired.iter().cloned().collect();
+
+        // Remove handles whose key is no longer wanted.
+        let stale: Vec<K> = self
+            .children
+            .keys()
+            .filter(|k| !desired_set.contains(*k))
+            .cloned()
+            .collect();
+        for key in stale {
+            if let Some(handle) = self.children.remove(&key) {
+                detach(&handle.widget());
+            }
+        }
+
+        // Create children for keys we don't have yet.
+        for key in &desired {
+            if !self.children.contains_key(key) {
+                let handle = ctx.create_child::<C, _>(make_lens(key.clone()), make_params(key));
+                attach(&handle.widget());
+                self.children.insert(key.clone(), handle);
+            }
+        }
+
+        // Reorder widgets to match the desired order.  A `None` previous
+        // sibling moves the widget to the front of the container.
+        let mut prev: Option<gtk::Widget> = None;
+        for key in &desired {
+            if let Some(handle) = self.children.get(key) {
+                let widget: gtk::Widget = handle.widget().upcast();
+                widget.insert_after(&self.parent, prev.as_ref());
+                prev = Some(widget);
+            }
+        }
+    }
 }